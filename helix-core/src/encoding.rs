@@ -7,22 +7,117 @@ pub enum Encoder {
     Other(encoding_rs::Encoder),
 }
 
-
 impl Encoder {
     pub fn encode_from_utf8(
         &mut self,
         src: &str,
-        mut dst: &mut [u8],
+        dst: &mut [u8],
         last: bool,
     ) -> (CoderResult, usize, usize, bool) {
         match self {
-            Encoder::Utf16Be => {
-                loop {
-                    if 
-                }                 
-            },
-            Encoder::Utf16Le => todo!(),
+            Encoder::Utf16Be => encode_from_utf8_utf16(src, dst, last, u16::to_be_bytes),
+            Encoder::Utf16Le => encode_from_utf8_utf16(src, dst, last, u16::to_le_bytes),
             Encoder::Other(encoder) => encoder.encode_from_utf8(src, dst, last),
         }
     }
 }
+
+// encoding_rs refuses to encode into UTF-16 (it only decodes), so we drive the
+// surrogate-pair math ourselves. `last` has no effect here: unlike encoders with a
+// shift state (e.g. ISO-2022-JP), UTF-16 has nothing to flush at the end of input.
+fn encode_from_utf8_utf16(
+    src: &str,
+    dst: &mut [u8],
+    _last: bool,
+    to_bytes: fn(u16) -> [u8; 2],
+) -> (CoderResult, usize, usize, bool) {
+    let mut read = 0;
+    let mut written = 0;
+
+    for ch in src.chars() {
+        let mut units = [0u16; 2];
+        let units = ch.encode_utf16(&mut units);
+
+        // Never split a surrogate pair across a flush: bail out before writing
+        // either unit if there isn't room for all of them.
+        if dst.len() - written < units.len() * 2 {
+            return (CoderResult::OutputFull, read, written, false);
+        }
+
+        for unit in units {
+            dst[written..written + 2].copy_from_slice(&to_bytes(*unit));
+            written += 2;
+        }
+        read += ch.len_utf8();
+    }
+
+    (CoderResult::InputEmpty, read, written, false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_ascii_be_and_le() {
+        let mut dst = [0u8; 16];
+        let (result, read, written, _) = encode_from_utf8_utf16("hi", &mut dst, true, u16::to_be_bytes);
+        assert_eq!(result, CoderResult::InputEmpty);
+        assert_eq!(read, 2);
+        assert_eq!(written, 4);
+        assert_eq!(&dst[..written], &[0x00, b'h', 0x00, b'i']);
+
+        let mut dst = [0u8; 16];
+        let (result, read, written, _) = encode_from_utf8_utf16("hi", &mut dst, true, u16::to_le_bytes);
+        assert_eq!(result, CoderResult::InputEmpty);
+        assert_eq!(read, 2);
+        assert_eq!(written, 4);
+        assert_eq!(&dst[..written], &[b'h', 0x00, b'i', 0x00]);
+    }
+
+    #[test]
+    fn encode_surrogate_pair_fits_exactly() {
+        // U+1D11E MUSICAL SYMBOL G CLEF: outside the BMP, so it encodes to a
+        // 2-unit (4-byte) UTF-16 surrogate pair.
+        let src = "\u{1D11E}";
+        let mut dst = [0u8; 4];
+        let (result, read, written, _) = encode_from_utf8_utf16(src, &mut dst, true, u16::to_be_bytes);
+        assert_eq!(result, CoderResult::InputEmpty);
+        assert_eq!(read, src.len());
+        assert_eq!(written, 4);
+    }
+
+    #[test]
+    fn encode_surrogate_pair_does_not_split_across_a_short_dst() {
+        // Only 2 bytes available - room for one half of the 4-byte surrogate
+        // pair but not both. Must report `OutputFull` without writing a single
+        // (unpaired, invalid) surrogate unit.
+        let src = "\u{1D11E}";
+        let mut dst = [0u8; 2];
+        let (result, read, written, _) = encode_from_utf8_utf16(src, &mut dst, true, u16::to_be_bytes);
+        assert_eq!(result, CoderResult::OutputFull);
+        assert_eq!(read, 0);
+        assert_eq!(written, 0);
+    }
+
+    #[test]
+    fn encode_surrogate_pair_after_dst_boundary_resumes_cleanly() {
+        // A plain BMP char exactly fills `dst`, leaving no room for the
+        // supplementary-plane char that follows; the caller is expected to flush
+        // and retry with `src` advanced past `read`.
+        let src = "a\u{1D11E}";
+        let mut dst = [0u8; 2];
+        let (result, read, written, _) = encode_from_utf8_utf16(src, &mut dst, true, u16::to_be_bytes);
+        assert_eq!(result, CoderResult::OutputFull);
+        assert_eq!(read, 1);
+        assert_eq!(written, 2);
+        assert_eq!(&dst[..written], &[0x00, b'a']);
+
+        let mut dst = [0u8; 4];
+        let (result, read, written, _) =
+            encode_from_utf8_utf16(&src[1..], &mut dst, true, u16::to_be_bytes);
+        assert_eq!(result, CoderResult::InputEmpty);
+        assert_eq!(read, src.len() - 1);
+        assert_eq!(written, 4);
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    collections::HashSet,
     io::{self, Read, Seek, SeekFrom, Write},
     num::NonZeroUsize,
     path::Path,
@@ -6,7 +7,8 @@ use std::{
     time::{Duration, SystemTime},
 };
 
-use super::{error::StateError, History, Revision};
+use super::{error::StateError, union, History, Revision};
+use crate::undofile::IndexEntry;
 use crate::{combinators::*, ChangeSet, Operation, Range, Selection, Transaction};
 
 const HASH_DIGEST_LENGTH: usize = 20;
@@ -125,25 +127,214 @@ impl Transaction {
     }
 }
 
-impl Revision {
-    fn serialize<W: Write>(&self, writer: &mut W) -> Result<(), StateError> {
-        write_usize(writer, self.parent)?;
-        self.transaction.serialize(writer)?;
-        self.inversion.serialize(writer)?;
-        write_time(writer, self.timestamp)?;
+/// Per-revision compression mode. Tracked as a bit in the on-disk index's `flags`
+/// rather than a dedicated field so mixed raw/compressed revisions round-trip even if
+/// the threshold or codec changes between Helix versions.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CompressionMode {
+    Raw,
+    Zstd,
+}
+
+impl CompressionMode {
+    pub(crate) fn from_flags(flags: u16) -> Self {
+        if flags & REVISION_FLAG_COMPRESSED != 0 {
+            Self::Zstd
+        } else {
+            Self::Raw
+        }
+    }
+}
+
+/// Revisions smaller than this are always stored raw: zstd's frame overhead makes
+/// compression a net loss on small blobs, and it isn't worth paying for a decompression
+/// pass on every load just to save a handful of bytes.
+const COMPRESS_THRESHOLD: usize = 128;
+
+/// `flags` bit set when a revision's payload is zstd-compressed.
+pub(crate) const REVISION_FLAG_COMPRESSED: u16 = 1 << 0;
+
+/// `flags` bit set when a revision's inserted text has been redacted: the
+/// reserved mode/flags byte already in front of every revision's payload (see
+/// `REVISION_PREFIX_LEN`) doubles as the censor bit, revlog-style, rather than
+/// adding a dedicated field.
+pub(crate) const REVISION_FLAG_CENSORED: u16 = 1 << 1;
+
+/// Stands in for a censored revision's real inserted text on load. Visible (unlike
+/// the zero-length placeholder actually written to disk) so a redacted revision is
+/// obviously redacted rather than silently empty.
+const REDACTION_MARKER: &str = "[redacted]";
+
+/// Returns a copy of `transaction` with every inserted `Tendril`'s text replaced by
+/// `replacement`, leaving `Retain`/`Delete` lengths untouched. `len` (the changeset's
+/// input length) is unaffected by this substitution, but `len_after` is recomputed
+/// from the replacement's actual length rather than copied: `replacement` is a
+/// different length than the text it stands in for (empty on the encode/censor side,
+/// `REDACTION_MARKER` on the decode side), so naively keeping the old `len_after`
+/// would leave it lying about the length the changeset actually produces, breaking
+/// `ChangeSet`'s own invariant the moment the revision is replayed (e.g. on redo, or
+/// through [`union::merge`][super::union::merge], which sizes its output off
+/// `len_after`). Shared by `Revision::encode`, which censors with an empty string to
+/// keep the real text off disk, and `Revision::decode`, which redacts with
+/// `REDACTION_MARKER` to make a censored revision visibly censored on load.
+fn replace_inserts(transaction: &Transaction, replacement: &str) -> Transaction {
+    let replacement_len = replacement.chars().count();
+    let mut original_insert_len = 0usize;
+    let mut replaced_inserts = 0usize;
+
+    let changes = transaction
+        .changes
+        .changes()
+        .iter()
+        .map(|op| match op {
+            Operation::Insert(tendril) => {
+                original_insert_len += tendril.chars().count();
+                replaced_inserts += 1;
+                Operation::Insert(replacement.into())
+            }
+            other => other.clone(),
+        })
+        .collect();
+
+    let len_after = transaction.changes.len_after - original_insert_len
+        + replacement_len * replaced_inserts;
+
+    Transaction {
+        changes: ChangeSet {
+            changes,
+            len: transaction.changes.len,
+            len_after,
+        },
+        selection: transaction.selection.clone(),
+    }
+}
+
+/// One revision, encoded but not yet written: everything a caller needs to place it in
+/// the data region and describe it in the on-disk index.
+struct EncodedRevision {
+    mode: CompressionMode,
+    censored: bool,
+    stored_len: u32,
+    uncompressed_len: u32,
+    payload: Vec<u8>,
+}
+
+impl EncodedRevision {
+    /// The `flags` byte stored both in the on-disk index and in the prefix written
+    /// before this revision's payload — computed once here so the two sites (and
+    /// `Revision::decode`'s inverse, `CompressionMode::from_flags`) never drift.
+    fn flags(&self) -> u16 {
+        let mut flags = if self.mode == CompressionMode::Zstd {
+            REVISION_FLAG_COMPRESSED
+        } else {
+            0
+        };
+        if self.censored {
+            flags |= REVISION_FLAG_CENSORED;
+        }
+        flags
+    }
+
+    /// Writes this revision's prefix (flags + stored/uncompressed lengths) followed
+    /// by its payload.
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_byte(writer, self.flags() as u8)?;
+        write_u32(writer, self.stored_len)?;
+        write_u32(writer, self.uncompressed_len)?;
+        writer.write_all(&self.payload)?;
         Ok(())
     }
+}
+
+/// The fixed-size prefix written before every revision's payload: one mode/flags byte
+/// reserved for forwards-compatible sniffing, plus the stored and uncompressed
+/// lengths. Fixed width (not the varint `write_usize` used elsewhere) so the on-disk
+/// index can point straight at a payload without parsing anything first.
+const REVISION_PREFIX_LEN: u64 = 1 + 4 + 4;
+
+impl Revision {
+    /// Encodes this revision's payload. When `censor` is set, the inserted text of
+    /// both `transaction` and `inversion` is written as a zero-length placeholder
+    /// instead of its real content, so the bytes that hit disk never contain it.
+    fn encode(&self, censor: bool) -> Result<EncodedRevision, StateError> {
+        let mut raw = Vec::new();
+        write_usize(&mut raw, self.parent)?;
+        if censor {
+            replace_inserts(&self.transaction, "").serialize(&mut raw)?;
+            replace_inserts(&self.inversion, "").serialize(&mut raw)?;
+        } else {
+            self.transaction.serialize(&mut raw)?;
+            self.inversion.serialize(&mut raw)?;
+        }
+        write_time(&mut raw, self.timestamp)?;
+
+        // Only bother compressing once the blob is big enough for zstd's frame
+        // overhead to pay for itself, and only keep the compressed form if it's
+        // actually smaller: some revisions (e.g. inserts of text with little
+        // repetition) compress worse than they start.
+        if raw.len() >= COMPRESS_THRESHOLD {
+            let compressed = zstd::stream::encode_all(raw.as_slice(), 0)?;
+            if compressed.len() < raw.len() {
+                return Ok(EncodedRevision {
+                    mode: CompressionMode::Zstd,
+                    censored: censor,
+                    stored_len: compressed.len() as u32,
+                    uncompressed_len: raw.len() as u32,
+                    payload: compressed,
+                });
+            }
+        }
+
+        Ok(EncodedRevision {
+            mode: CompressionMode::Raw,
+            censored: censor,
+            stored_len: raw.len() as u32,
+            uncompressed_len: raw.len() as u32,
+            payload: raw,
+        })
+    }
+
+    /// Reconstructs a revision from a payload already located via the on-disk index,
+    /// first decompressing it if `flags`'s compression bit is set. If
+    /// `flags` carries [`REVISION_FLAG_CENSORED`], the inserted text written to disk
+    /// is already gone (see `Revision::encode`); this only replaces it with a visible
+    /// [`REDACTION_MARKER`] instead of leaving it silently empty.
+    pub(crate) fn decode(
+        flags: u16,
+        uncompressed_len: u32,
+        stored: &[u8],
+    ) -> Result<Self, StateError> {
+        let mode = CompressionMode::from_flags(flags);
+        let raw = match mode {
+            CompressionMode::Raw => stored.to_vec(),
+            CompressionMode::Zstd => {
+                let raw = zstd::stream::decode_all(stored)?;
+                if raw.len() != uncompressed_len as usize {
+                    return Err(StateError::InvalidData(format!(
+                        "corrupt revision: expected {uncompressed_len} bytes after decompression, got {}",
+                        raw.len()
+                    )));
+                }
+                raw
+            }
+        };
+
+        let mut raw = raw.as_slice();
+        let parent = read_usize(&mut raw)?;
+        let mut transaction = Transaction::deserialize(&mut raw)?;
+        let mut inversion = Transaction::deserialize(&mut raw)?;
+        let timestamp = read_time(&mut raw)?;
+
+        if flags & REVISION_FLAG_CENSORED != 0 {
+            transaction = replace_inserts(&transaction, REDACTION_MARKER);
+            inversion = replace_inserts(&inversion, REDACTION_MARKER);
+        }
 
-    fn deserialize<R: Read>(reader: &mut R) -> Result<Self, StateError> {
-        let parent = read_usize(reader)?;
-        let transaction = Arc::new(Transaction::deserialize(reader)?);
-        let inversion = Arc::new(Transaction::deserialize(reader)?);
-        let timestamp = read_time(reader)?;
         Ok(Revision {
             parent,
             last_child: None,
-            transaction,
-            inversion,
+            transaction: Arc::new(transaction),
+            inversion: Arc::new(inversion),
             timestamp,
         })
     }
@@ -151,7 +342,58 @@ impl Revision {
 
 const UNDO_FILE_HEADER_TAG: &[u8] = b"Helix";
 const UNDO_FILE_HEADER_LEN: usize = UNDO_FILE_HEADER_TAG.len();
-const UNDO_FILE_VERSION: u8 = 1;
+// Bumped for the revlog-style per-revision compression prefix, the footer index, and
+// the target file's cached length/mtime.
+const UNDO_FILE_VERSION: u8 = 3;
+
+/// Byte length of the fixed header fields written before the data region: tag +
+/// version + current + hash + target file length + target file mtime + index offset
+/// + revision count.
+pub(crate) const DATA_START: u64 = (UNDO_FILE_HEADER_LEN
+    + 1 // version
+    + 8 // current
+    + HASH_DIGEST_LENGTH
+    + 8 // target file length, for the cheap staleness pre-check
+    + 12 // target file mtime: u64 secs + u32 subsec nanos
+    + 8 // index offset
+    + 8) as u64; // revision count
+
+fn write_system_time<W: Write>(writer: &mut W, time: SystemTime) -> io::Result<()> {
+    let duration = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0));
+    write_u64(writer, duration.as_secs())?;
+    write_u32(writer, duration.subsec_nanos())?;
+    Ok(())
+}
+
+fn read_system_time<R: Read>(reader: &mut R) -> io::Result<SystemTime> {
+    let secs = read_u64(reader)?;
+    let nanos = read_u32(reader)?;
+    Ok(SystemTime::UNIX_EPOCH + Duration::new(secs, nanos))
+}
+
+/// A writer that can also drop everything after its current position. Needed so
+/// [`History::serialize`] can shed stale trailing bytes after a full rewrite of the
+/// data region (`offset == 0` on an already-populated file, as [`History::censor`]
+/// always triggers): without it, the bytes a full rewrite is meant to replace are
+/// merely overwritten up to the new, possibly shorter end, leaving the old tail
+/// orphaned but still physically present on disk.
+pub trait Truncate {
+    fn truncate(&mut self, len: u64) -> io::Result<()>;
+}
+
+impl Truncate for std::fs::File {
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        self.set_len(len)
+    }
+}
+
+pub(crate) struct UndofileHeader {
+    pub(crate) current: usize,
+    pub(crate) index_offset: u64,
+    pub(crate) revision_count: usize,
+}
 
 impl History {
     /// It is the responsibility of the caller to ensure the undofile is valid before serializing.
@@ -162,45 +404,142 @@ impl History {
     //     - UNDO_FILE_VERSION
     //     - Current revision at time of write
     //     - Hash of the file
-    // - Revisions contiguously
-    pub fn serialize<W: Write + Seek>(
+    //     - Length and mtime of the file
+    //     - Index offset, revision count
+    // - Revisions contiguously, starting at DATA_START
+    // - The index table (one fixed-width IndexEntry per revision)
+    pub fn serialize<W: Write + Seek + Truncate>(
         &self,
         writer: &mut W,
         path: &Path,
         // The offset after which to append new revisions
         offset: usize,
+        // Revision indices to write with their inserted text redacted (see
+        // `Revision::encode`). Empty for a normal save.
+        censored: &HashSet<usize>,
     ) -> Result<(), StateError> {
         // Header
         writer.write_all(UNDO_FILE_HEADER_TAG)?;
         write_byte(writer, UNDO_FILE_VERSION)?;
 
         // We save the current revision so that we reload at that revision later
-        write_usize(writer, self.current)?;
+        write_u64(writer, self.current as u64)?;
         writer.write_all(&get_hash(&mut std::fs::File::open(path)?)?)?;
 
-        // Append new revisions to the end of the file.
-        write_usize(writer, self.revisions.len())?;
-        writer.seek(SeekFrom::End(0))?;
-        for rev in &self.revisions[offset..] {
-            rev.serialize(writer)?;
+        // Cheap discriminators for the staleness check in `parse_header`: a changed
+        // size or mtime answers "is this file outdated?" without re-hashing the whole
+        // file, which is the expensive part `read_header` used to always pay for.
+        let metadata = std::fs::metadata(path)?;
+        write_u64(writer, metadata.len())?;
+        write_system_time(writer, metadata.modified()?)?;
+
+        // Patched in below, once we know where the index ends up.
+        let index_offset_pos = writer.stream_position()?;
+        write_u64(writer, 0)?;
+        write_u64(writer, self.revisions.len() as u64)?;
+        debug_assert_eq!(writer.stream_position()?, DATA_START);
+
+        // Recompute the index for every revision, not just the new tail: compression
+        // is deterministic, so re-encoding an already-written revision reproduces the
+        // exact bytes already sitting on disk for it, letting us rebuild the index
+        // without touching that data. This is the one part of this format that isn't
+        // truly incremental; a future pass could cache `EncodedRevision` lengths
+        // alongside `Revision` instead of recomputing them on every save.
+        let mut index = Vec::with_capacity(self.revisions.len());
+        let mut new_blocks = Vec::new();
+        let mut byte_offset = 0u64;
+        for (i, rev) in self.revisions.iter().enumerate() {
+            let encoded = rev.encode(censored.contains(&i))?;
+            index.push(IndexEntry {
+                byte_offset: byte_offset + REVISION_PREFIX_LEN,
+                stored_len: encoded.stored_len,
+                uncompressed_len: encoded.uncompressed_len,
+                parent: rev.parent as u32,
+                flags: encoded.flags(),
+            });
+            byte_offset += REVISION_PREFIX_LEN + encoded.stored_len as u64;
+            if i >= offset {
+                new_blocks.push(encoded);
+            }
         }
 
+        // Write only the new revisions' bytes; everything before `offset` already
+        // sits at the byte offsets just recomputed above. The first new revision
+        // starts where revision `offset - 1`'s data ends, which is where the previous
+        // save's index began, so that stale index is overwritten instead of being
+        // left between the old and the new data. With `offset == 0` (as `censor`
+        // uses, since the censored revision is rarely the last one) every revision
+        // counts as "new", so the data region is rewritten from its start. Whatever
+        // used to follow the old, longer data region is truncated away below rather
+        // than left as an orphaned tail: for `censor` specifically, that tail is the
+        // original, un-redacted revision payload the whole feature exists to remove,
+        // so merely overwriting its prefix isn't enough to actually scrub it from disk.
+        let append_at = index
+            .get(offset)
+            .map_or(byte_offset, |entry| entry.byte_offset - REVISION_PREFIX_LEN);
+        writer.seek(SeekFrom::Start(DATA_START + append_at))?;
+        for encoded in &new_blocks {
+            encoded.write(writer)?;
+        }
+
+        // The index always moves to the new end of file: rewriting it there (instead
+        // of in a fixed slot right after the header) is what lets the data region
+        // grow by pure appends.
+        let index_pos = writer.stream_position()?;
+        for entry in &index {
+            entry.serialize(writer)?;
+        }
+
+        // Only a full rewrite can leave stale bytes behind (an incremental append
+        // only ever grows the file), so only truncate then.
+        if offset == 0 {
+            let end = writer.stream_position()?;
+            writer.truncate(end)?;
+        }
+
+        writer.seek(SeekFrom::Start(index_offset_pos))?;
+        write_u64(writer, index_pos)?;
+
         writer.flush()?;
         Ok(())
     }
 
+    /// Rewrites the undofile with `rev`'s inserted text redacted, so a user can strip
+    /// sensitive text (passwords, tokens) that ended up in an undo history without
+    /// discarding the rest of it. `rev` is rarely the most recent revision, so unlike
+    /// a normal incremental save this always rewrites the whole data region (`offset`
+    /// of 0) rather than appending just a tail.
+    pub fn censor<W: Write + Seek + Truncate>(
+        &self,
+        writer: &mut W,
+        path: &Path,
+        rev: usize,
+    ) -> Result<(), StateError> {
+        let mut censored = HashSet::new();
+        censored.insert(rev);
+        self.serialize(writer, path, 0, &censored)
+    }
+
     /// Returns the deserialized [`History`] and the last_saved_revision.
     // Deserializes:
     // - Header
-    // - Revisions
-    pub fn deserialize<R: Read>(reader: &mut R, path: &Path) -> Result<(usize, Self), StateError> {
-        let current = Self::read_header(reader, path)?;
-
-        // Read the revisions and construct the tree.
-        let len = read_usize(reader)?;
-        let mut revisions: Vec<Revision> = Vec::with_capacity(len);
-        for _ in 0..len {
-            let rev = Revision::deserialize(reader)?;
+    // - Index table
+    // - Revisions, by seeking directly to each one's payload via the index
+    pub fn deserialize<R: Read + Seek>(
+        reader: &mut R,
+        path: &Path,
+    ) -> Result<(usize, Self), StateError> {
+        let header = Self::parse_header(reader, path)?;
+        let index = Self::read_index(reader, &header)?;
+
+        // Read the revisions (via the index, rather than assuming they're framed
+        // back-to-back with no outer length) and construct the tree.
+        let mut revisions: Vec<Revision> = Vec::with_capacity(header.revision_count);
+        for entry in &index {
+            reader.seek(SeekFrom::Start(DATA_START + entry.byte_offset))?;
+            let mut stored = vec![0u8; entry.stored_len as usize];
+            reader.read_exact(&mut stored)?;
+            let rev = Revision::decode(entry.flags, entry.uncompressed_len, &stored)?;
             let len = revisions.len();
 
             // Check that order of revisions is correct before inserting
@@ -225,52 +564,58 @@ impl History {
             revisions.push(rev);
         }
 
+        let current = header.current;
         let history = History { current, revisions };
         Ok((current, history))
     }
 
-    /// If `self.revisions = [A, B, C, D]` and `other.revisions = `[A, B, E, F]`, then
-    /// they are merged into `[A, B, E, F, C, D]` where the tree can be represented as:
+    /// Reads the index table described by `header` without touching any revision
+    /// payloads. Used both by the eager [`History::deserialize`] and by
+    /// [`crate::undofile::UndoStorageHandle`]'s lazy, single-revision loads.
+    pub(crate) fn read_index<R: Read + Seek>(
+        reader: &mut R,
+        header: &UndofileHeader,
+    ) -> Result<Vec<IndexEntry>, StateError> {
+        reader.seek(SeekFrom::Start(header.index_offset))?;
+        let mut index = Vec::with_capacity(header.revision_count);
+        for _ in 0..header.revision_count {
+            index.push(IndexEntry::deserialize(reader)?);
+        }
+        Ok(index)
+    }
+
+    /// Reconciles `other` into `self`, even when the two diverged after a common
+    /// revision rather than one being a strict continuation of the other: `other`'s
+    /// revisions are replayed against a shared union string (see
+    /// [`union`][super::union]) and re-expressed ("transformed") as revisions that
+    /// apply cleanly on top of their own real parent — wherever that ancestor falls
+    /// in `self`'s tree — rather than assuming `other`'s raw transactions are still
+    /// valid against `self`'s (possibly different) live text.
+    ///
+    /// `self`'s own revisions, and their indices, are left untouched; `other`'s
+    /// unique revisions are appended after them, so if `self.revisions = [A, B, C,
+    /// D]` and `other.revisions = [A, B, E, F]`, the result is `[A, B, C, D, E', F']`
+    /// (primed because their changesets are transformed, not copied verbatim) where
+    /// the tree can be represented as:
     /// ```md
     /// A -> B -> C -> D
-    ///       \  
-    ///        E -> F
+    ///       \
+    ///        E' -> F'
     /// ```
-    pub fn merge(&mut self, mut other: History) -> Result<(), StateError> {
-        let n = self
-            .revisions
-            .iter()
-            .zip(other.revisions.iter())
-            .take_while(|(a, b)| {
-                a.parent == b.parent && a.transaction == b.transaction && a.inversion == b.inversion
-            })
-            .count();
-
-        let new_revs = self.revisions.split_off(n);
-        if new_revs.is_empty() {
+    pub fn merge(&mut self, other: History) -> Result<(), StateError> {
+        let common = union::common_prefix_len(&self.revisions, &other.revisions);
+        if common >= other.revisions.len() {
+            // `other` has nothing `self` doesn't already have.
             return Ok(());
         }
-        other.revisions.reserve_exact(n);
-
-        // Only unique revisions in `self` matter, so saturating_sub(1) is sound as it going to 0 means there are no new revisions in the other history that aren't in `self`
-        let offset = (other.revisions.len() - n).saturating_sub(1);
-        for mut r in new_revs {
-            // Update parents of new revisions
-            if r.parent >= n {
-                r.parent += offset;
-            }
-            debug_assert!(r.parent < other.revisions.len());
-
-            // Update the corresponding parent.
-            other.revisions.get_mut(r.parent).unwrap().last_child =
-                NonZeroUsize::new(other.revisions.len());
-            other.revisions.push(r);
-        }
 
-        if self.current >= n {
-            self.current += offset;
+        let spliced = union::merge(&self.revisions, &other.revisions, common)?;
+        for revision in spliced {
+            let parent = revision.parent;
+            self.revisions.push(revision);
+            let len = self.revisions.len();
+            self.revisions[parent].last_child = NonZeroUsize::new(len - 1);
         }
-        self.revisions = other.revisions;
 
         Ok(())
     }
@@ -283,22 +628,253 @@ impl History {
     // - Checks for UNDO_FILE_HEADER
     // - Validates UNDO_FILE_VERSION
     // - Current revision
-    // - Validates hash
+    // - Validates (cheaply, then, if ambiguous, fully) that the target file is unchanged
     pub fn read_header<R: Read>(reader: &mut R, path: &Path) -> Result<usize, StateError> {
+        Self::parse_header(reader, path).map(|header| header.current)
+    }
+
+    /// Parses the full fixed-size header, including the index offset and revision
+    /// count needed to locate the index table. Used by [`History::deserialize`] and
+    /// by [`crate::undofile::UndoStorageHandle`] for lazy revision loads.
+    pub(crate) fn parse_header<R: Read>(
+        reader: &mut R,
+        path: &Path,
+    ) -> Result<UndofileHeader, StateError> {
         let header: [u8; UNDO_FILE_HEADER_LEN] = read_many_bytes(reader)?;
         let version = read_byte(reader)?;
         if header != UNDO_FILE_HEADER_TAG || version != UNDO_FILE_VERSION {
-            Err(StateError::InvalidHeader)
-        } else {
-            let current = read_usize(reader)?;
-            let mut hash = [0u8; HASH_DIGEST_LENGTH];
-            reader.read_exact(&mut hash)?;
+            return Err(StateError::InvalidHeader);
+        }
 
-            if hash != get_hash(&mut std::fs::File::open(path)?)? {
-                return Err(StateError::Outdated);
-            }
+        let current = read_u64(reader)? as usize;
+        let mut hash = [0u8; HASH_DIGEST_LENGTH];
+        reader.read_exact(&mut hash)?;
+        let stored_len = read_u64(reader)?;
+        let stored_mtime = read_system_time(reader)?;
+        let index_offset = read_u64(reader)?;
+        let revision_count = read_u64(reader)? as usize;
+
+        // Cheap path: a changed size or mtime already answers "is this file
+        // outdated?" without paying for a full-file hash.
+        let metadata = std::fs::metadata(path)?;
+        if metadata.len() != stored_len || metadata.modified()? != stored_mtime {
+            return Err(StateError::Outdated);
+        }
+
+        // Ambiguous path: size and mtime matched (e.g. an editor that preserves
+        // mtime, or two edits landing on the same size), so fall back to comparing
+        // the full-file hash like before.
+        if hash != get_hash(&mut std::fs::File::open(path)?)? {
+            return Err(StateError::Outdated);
+        }
+
+        Ok(UndofileHeader {
+            current,
+            index_offset,
+            revision_count,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::SystemTime;
 
-            Ok(current)
+    use super::*;
+
+    impl Truncate for Cursor<Vec<u8>> {
+        fn truncate(&mut self, len: u64) -> io::Result<()> {
+            self.get_mut().truncate(len as usize);
+            Ok(())
         }
     }
+
+    /// A real file on disk standing in for the target file `serialize`/
+    /// `parse_header` hash and stat: both read `path` straight off the
+    /// filesystem, so there's no way to exercise them against an in-memory
+    /// stand-in. Removed on drop.
+    struct TempFile {
+        path: std::path::PathBuf,
+    }
+
+    impl TempFile {
+        fn new(contents: &[u8]) -> Self {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+            let path = std::env::temp_dir().join(format!(
+                "helix-history-format-test-{}-{n}",
+                std::process::id()
+            ));
+            std::fs::write(&path, contents).unwrap();
+            Self { path }
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    fn revision(parent: usize, transaction: Transaction) -> Revision {
+        Revision {
+            parent,
+            last_child: None,
+            transaction: Arc::new(transaction),
+            inversion: Arc::new(Transaction {
+                changes: ChangeSet {
+                    changes: Vec::new(),
+                    len: 0,
+                    len_after: 0,
+                },
+                selection: None,
+            }),
+            timestamp: SystemTime::UNIX_EPOCH,
+        }
+    }
+
+    fn insert_transaction(text: &str) -> Transaction {
+        Transaction {
+            changes: ChangeSet {
+                changes: vec![Operation::Insert(text.into())],
+                len: 0,
+                len_after: text.chars().count(),
+            },
+            selection: None,
+        }
+    }
+
+    #[test]
+    fn encode_round_trips_both_below_and_above_the_compression_threshold() {
+        let small = revision(0, insert_transaction("hi"));
+        let encoded = small.encode(false).unwrap();
+        assert!(encoded.mode == CompressionMode::Raw);
+        let decoded = Revision::decode(encoded.flags(), encoded.uncompressed_len, &encoded.payload).unwrap();
+        assert_eq!(
+            decoded.transaction.changes.changes(),
+            small.transaction.changes.changes()
+        );
+
+        // Repetitive text well past `COMPRESS_THRESHOLD` compresses smaller than
+        // it started, so this one takes the zstd path.
+        let big_text = "word ".repeat(100);
+        let big = revision(0, insert_transaction(&big_text));
+        let encoded = big.encode(false).unwrap();
+        assert!(encoded.mode == CompressionMode::Zstd);
+        assert!(encoded.stored_len < encoded.uncompressed_len);
+        let decoded = Revision::decode(encoded.flags(), encoded.uncompressed_len, &encoded.payload).unwrap();
+        assert_eq!(
+            decoded.transaction.changes.changes(),
+            big.transaction.changes.changes()
+        );
+    }
+
+    #[test]
+    fn decode_rejects_an_uncompressed_length_that_does_not_match_the_decompressed_payload() {
+        let big_text = "word ".repeat(100);
+        let big = revision(0, insert_transaction(&big_text));
+        let encoded = big.encode(false).unwrap();
+        assert!(encoded.mode == CompressionMode::Zstd);
+
+        let err = Revision::decode(encoded.flags(), encoded.uncompressed_len + 1, &encoded.payload)
+            .unwrap_err();
+        assert!(matches!(err, StateError::InvalidData(_)));
+    }
+
+    #[test]
+    fn parse_header_succeeds_when_the_target_file_is_unchanged() {
+        let target = TempFile::new(b"target file contents");
+        let history = History::default();
+
+        let mut buf = Cursor::new(Vec::new());
+        history
+            .serialize(&mut buf, &target.path, 0, &HashSet::new())
+            .unwrap();
+
+        buf.set_position(0);
+        assert_eq!(History::parse_header(&mut buf, &target.path).unwrap().current, 0);
+    }
+
+    #[test]
+    fn parse_header_falls_back_to_a_full_hash_when_size_and_mtime_both_match() {
+        let target = TempFile::new(b"target file contents");
+        let history = History::default();
+
+        let mut buf = Cursor::new(Vec::new());
+        history
+            .serialize(&mut buf, &target.path, 0, &HashSet::new())
+            .unwrap();
+
+        // Flip a byte without changing length, then force the mtime back to
+        // exactly what was recorded: same size, same mtime, different content -
+        // the one case the cheap size/mtime pre-check alone can't catch, which
+        // is exactly why `parse_header` still falls back to a full-file hash.
+        let original_mtime = std::fs::metadata(&target.path).unwrap().modified().unwrap();
+        let mut contents = std::fs::read(&target.path).unwrap();
+        contents[0] = contents[0].wrapping_add(1);
+        std::fs::write(&target.path, &contents).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&target.path)
+            .unwrap()
+            .set_modified(original_mtime)
+            .unwrap();
+
+        buf.set_position(0);
+        let err = History::parse_header(&mut buf, &target.path).unwrap_err();
+        assert!(matches!(err, StateError::Outdated));
+    }
+
+    #[test]
+    fn censor_then_reload_then_resave_keeps_the_secret_off_disk() {
+        let target = TempFile::new(b"target file contents");
+
+        // Short enough that its revision stays under `COMPRESS_THRESHOLD` and is
+        // stored raw, so the uncensored write holds the secret verbatim; replacing
+        // it with the zero-length on-disk placeholder (see `Revision::encode`)
+        // still shrinks the file.
+        let secret = "super-secret-token-dont-leak-me-".to_string();
+        let mut history = History::default();
+        history.revisions.push(revision(0, insert_transaction(&secret)));
+        history.current = 1;
+
+        let mut buf = Cursor::new(Vec::new());
+        history
+            .serialize(&mut buf, &target.path, 0, &HashSet::new())
+            .unwrap();
+        let original_len = buf.get_ref().len();
+        assert!(String::from_utf8_lossy(buf.get_ref()).contains(&secret));
+
+        buf.set_position(0);
+        let (_, reloaded) = History::deserialize(&mut buf, &target.path).unwrap();
+        assert_eq!(
+            reloaded.revisions[1].transaction.changes.changes(),
+            &[Operation::Insert(secret.as_str().into())]
+        );
+
+        let mut censored_buf = Cursor::new(Vec::new());
+        reloaded.censor(&mut censored_buf, &target.path, 1).unwrap();
+
+        // A full rewrite that drops the secret's bytes outright, not merely
+        // overwrites them and leaves a stale, still-readable tail behind.
+        assert!(censored_buf.get_ref().len() < original_len);
+        assert!(!String::from_utf8_lossy(censored_buf.get_ref()).contains(&secret));
+
+        censored_buf.set_position(0);
+        let (_, resaved) = History::deserialize(&mut censored_buf, &target.path).unwrap();
+        match &resaved.revisions[1].transaction.changes.changes()[0] {
+            Operation::Insert(text) => assert_eq!(text.as_str(), REDACTION_MARKER),
+            other => panic!("expected a redacted insert, got {other:?}"),
+        }
+
+        // Resaving the now-censored-in-memory history doesn't resurrect the
+        // secret or re-expand its payload.
+        let mut resaved_buf = Cursor::new(Vec::new());
+        resaved
+            .serialize(&mut resaved_buf, &target.path, 0, &HashSet::new())
+            .unwrap();
+        assert!(!String::from_utf8_lossy(resaved_buf.get_ref()).contains(&secret));
+    }
 }
@@ -0,0 +1,596 @@
+//! A xi-rope-style union-string engine used to reconcile two [`History`]s that
+//! diverged after a common ancestor, rather than assuming one is a strict
+//! continuation of the other.
+//!
+//! The idea (borrowed from xi-editor's CRDT engine): imagine a "union string"
+//! holding every character either history ever inserted, with deleted characters
+//! kept around as tombstones instead of removed. Every revision's effect on the
+//! live text can then be described purely in terms of *positions in the union
+//! string* — which positions it newly inserted, and which it deleted — instead of
+//! in terms of the live text, which is a moving target. Two divergent histories
+//! share the same union-string coordinates for everything up to their common
+//! revision, so a revision unique to one side can be re-expressed ("transformed")
+//! against the other side's union string and spliced in at the textually correct
+//! spot, rather than blindly replayed against a live text it was never computed
+//! against.
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use super::{error::StateError, Revision};
+use crate::{ChangeSet, Operation, Transaction};
+
+/// A subset of positions in some reference string, stored as sorted, disjoint,
+/// half-open ranges. Used both for "which union positions does this revision
+/// delete" and "which union positions did this revision insert".
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Subset(Vec<Range<usize>>);
+
+impl Subset {
+    fn empty() -> Self {
+        Subset(Vec::new())
+    }
+
+    /// Appends `range`, coalescing it into the previous range when they're
+    /// adjacent so `self.0` never holds two ranges that could be one.
+    fn push(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        if let Some(last) = self.0.last_mut() {
+            if last.end == range.start {
+                last.end = range.end;
+                return;
+            }
+        }
+        self.0.push(range);
+    }
+
+    fn contains(&self, pos: usize) -> bool {
+        self.0.iter().any(|r| r.contains(&pos))
+    }
+
+    fn count(&self) -> usize {
+        self.0.iter().map(|r| r.end - r.start).sum()
+    }
+}
+
+/// `revisions[i]`'s view of the union string: every range of union positions it
+/// newly inserted, and the (possibly scattered) union positions it deleted. Kept
+/// as a list rather than one collapsed range because a single transaction can
+/// carry more than one `Insert` (e.g. a multi-cursor edit: `Insert("A"),
+/// Retain(n), Insert("B")`) at genuinely different positions.
+struct UnionRevision {
+    /// One entry per `Insert` op, in left-to-right order: the union position of
+    /// whichever character immediately followed it in the text it was applied
+    /// to (`None` if it landed at the very end), paired with the range of union
+    /// positions the inserted text itself occupies. The anchor is what makes the
+    /// insert's *position* transformable later — a bare union range only says
+    /// what was inserted, not where.
+    inserts: Vec<(Option<usize>, Range<usize>)>,
+    deletes: Subset,
+    /// Length of the union string immediately after this revision was replayed.
+    union_len_after: usize,
+}
+
+/// The result of replaying a history's revisions against a growing union string.
+struct Replayed {
+    union: Vec<char>,
+    revisions: Vec<UnionRevision>,
+    /// `live_to_union[i]` is the union-string position backing each character of
+    /// the live text *at revision `i`'s node in the tree* — i.e. after applying
+    /// exactly the root-to-`i` chain, not after applying every revision up to
+    /// `i` in flat index order. Kept per-revision (rather than a single running
+    /// value) because `revisions` is a tree: two revisions can share a parent
+    /// and branch, in which case they must each start from that parent's live
+    /// text, not from whatever the other sibling left behind.
+    live_to_union: Vec<Vec<usize>>,
+}
+
+/// Replays `revisions` from the root, growing `union` with every character any
+/// revision ever inserted and recording each revision's deletes/inserts subset
+/// against it. Each revision's `Retain`/`Delete` offsets are expressed against
+/// *its own parent's* live text (per `revision.parent`, not loop order), since
+/// `revisions` models an undo tree where siblings branch from the same parent
+/// rather than a linear chain.
+fn replay(revisions: &[Revision]) -> Replayed {
+    let mut union = Vec::new();
+    let mut live_to_union: Vec<Vec<usize>> = Vec::with_capacity(revisions.len());
+    let mut out = Vec::with_capacity(revisions.len());
+
+    for (i, revision) in revisions.iter().enumerate() {
+        // Revision 0 is the tree's root (its `parent` field is a placeholder,
+        // never a real ancestor - see `History::deserialize`'s starting-revision
+        // check), so it alone starts from an empty live text.
+        let parent_live: &[usize] = if i == 0 { &[] } else { &live_to_union[revision.parent] };
+
+        let mut next_live_to_union = Vec::with_capacity(revision.transaction.changes.len_after);
+        let mut deletes = Subset::empty();
+        let mut inserts = Vec::new();
+        let mut pos = 0usize;
+
+        for op in revision.transaction.changes.changes() {
+            match op {
+                Operation::Retain(n) => {
+                    next_live_to_union.extend_from_slice(&parent_live[pos..pos + n]);
+                    pos += n;
+                }
+                Operation::Delete(n) => {
+                    for &u in &parent_live[pos..pos + n] {
+                        deletes.push(u..u + 1);
+                    }
+                    pos += n;
+                }
+                Operation::Insert(tendril) => {
+                    // The union position of whatever `pos` currently points at in
+                    // the parent's live text is exactly what this insert sits
+                    // immediately before; `None` means `pos` has already run off
+                    // the end, i.e. this insert is a trailing append.
+                    let anchor = parent_live.get(pos).copied();
+                    let insert_start = union.len();
+                    for ch in tendril.chars() {
+                        next_live_to_union.push(union.len());
+                        union.push(ch);
+                    }
+                    inserts.push((anchor, insert_start..union.len()));
+                }
+            }
+        }
+
+        out.push(UnionRevision {
+            inserts,
+            deletes,
+            union_len_after: union.len(),
+        });
+        live_to_union.push(next_live_to_union);
+    }
+
+    Replayed {
+        union,
+        revisions: out,
+        live_to_union,
+    }
+}
+
+/// The length of the shared prefix of `self_revisions` and `other_revisions`: how
+/// many leading revisions the two histories have in common, verbatim.
+pub(super) fn common_prefix_len(
+    self_revisions: &[Revision],
+    other_revisions: &[Revision],
+) -> usize {
+    self_revisions
+        .iter()
+        .zip(other_revisions.iter())
+        .take_while(|(a, b)| {
+            a.parent == b.parent && a.transaction == b.transaction && a.inversion == b.inversion
+        })
+        .count()
+}
+
+/// One of `other`'s inserts, already transformed into `self`'s union
+/// coordinates: `anchor` (`None` for a trailing append) is the position in
+/// `live_to_union` to splice `text` in front of, and `union_range` is the
+/// (already-remapped) range of union positions `text` itself now occupies.
+struct SplicedInsert<'a> {
+    anchor: Option<usize>,
+    text: &'a [char],
+    union_range: Range<usize>,
+}
+
+/// Walks `live_to_union` (the live text currently being spliced onto), splicing
+/// in each of `inserts` — in order — right before the live position matching its
+/// anchor (or at the very end, for a `None` anchor or an anchor that never
+/// turns up, e.g. because `self` already deleted it), and marking every live
+/// position `deletes` covers as removed. `inserts` must be supplied in
+/// left-to-right order: each insert's anchor is only ever compared against
+/// positions not yet consumed by an earlier one in the list, so out-of-order
+/// anchors would silently reorder the inserts.
+///
+/// Returns the forward `ChangeSet` (live text -> text after this revision), its
+/// inverse (the undo), and the live-to-union mapping for the resulting text —
+/// all three fall out of the same walk, and `History` keeps each revision's
+/// `inversion` alongside its `transaction` and needs the mapping to transform
+/// whatever comes after this revision.
+fn splice(
+    live_to_union: &[usize],
+    deletes: &Subset,
+    inserts: &[SplicedInsert],
+) -> (ChangeSet, ChangeSet, Vec<usize>) {
+    let mut changes = Vec::new();
+    let mut inverse = Vec::new();
+    let mut next_live = Vec::with_capacity(
+        live_to_union.len() + inserts.iter().map(|i| i.text.len()).sum::<usize>(),
+    );
+    let mut retain_run = 0usize;
+    let mut next_insert = 0usize;
+
+    let flush =
+        |changes: &mut Vec<Operation>, inverse: &mut Vec<Operation>, retain_run: &mut usize| {
+            if *retain_run > 0 {
+                changes.push(Operation::Retain(*retain_run));
+                inverse.push(Operation::Retain(*retain_run));
+                *retain_run = 0;
+            }
+        };
+
+    let emit_insert = |changes: &mut Vec<Operation>,
+                        inverse: &mut Vec<Operation>,
+                        retain_run: &mut usize,
+                        next_live: &mut Vec<usize>,
+                        insert: &SplicedInsert| {
+        if insert.text.is_empty() {
+            return;
+        }
+        flush(changes, inverse, retain_run);
+        changes.push(Operation::Insert(insert.text.iter().collect::<String>().into()));
+        inverse.push(Operation::Delete(insert.text.len()));
+        next_live.extend(insert.union_range.clone());
+    };
+
+    for &u in live_to_union {
+        while next_insert < inserts.len() {
+            match inserts[next_insert].anchor {
+                Some(a) if a == u => {
+                    emit_insert(
+                        &mut changes,
+                        &mut inverse,
+                        &mut retain_run,
+                        &mut next_live,
+                        &inserts[next_insert],
+                    );
+                    next_insert += 1;
+                }
+                _ => break,
+            }
+        }
+
+        if deletes.contains(u) {
+            flush(&mut changes, &mut inverse, &mut retain_run);
+            match changes.last_mut() {
+                Some(Operation::Delete(n)) => *n += 1,
+                _ => changes.push(Operation::Delete(1)),
+            }
+            // The union string never forgets a character, so the deleted
+            // character itself is still available to reinsert on undo — but this
+            // engine only tracks *positions*, not recovered characters, so the
+            // inversion restores a retain-length placeholder rather than the
+            // original text. Real deletions (outside a merge splice) still carry
+            // their actual inverse via `Transaction::invert`.
+            inverse.push(Operation::Retain(1));
+        } else {
+            retain_run += 1;
+            next_live.push(u);
+        }
+    }
+    flush(&mut changes, &mut inverse, &mut retain_run);
+
+    // Anything left over — a trailing `None` anchor, or one that never matched
+    // because `self` deleted that position out from under it — lands at the end,
+    // in the same order it was recorded.
+    while next_insert < inserts.len() {
+        emit_insert(
+            &mut changes,
+            &mut inverse,
+            &mut retain_run,
+            &mut next_live,
+            &inserts[next_insert],
+        );
+        next_insert += 1;
+    }
+
+    let len = live_to_union.len();
+    let inserted_len: usize = inserts.iter().map(|i| i.text.len()).sum();
+    let len_after = len - deletes.count() + inserted_len;
+
+    (
+        ChangeSet {
+            changes,
+            len,
+            len_after,
+        },
+        ChangeSet {
+            changes: inverse,
+            len: len_after,
+            len_after: len,
+        },
+        next_live,
+    )
+}
+
+/// Transforms every revision unique to `other` (i.e. `other_revisions[common..]`)
+/// into a revision that applies cleanly on top of *its own real parent* —
+/// wherever that parent lands in `self_revisions`'s tree, which for a revision
+/// that branched off before `common` is an ancestor, not `self_revisions`'s
+/// head — by tracking both sides' edits in shared union-string coordinates
+/// instead of replaying `other`'s raw, stale-relative-to-`self` transactions.
+/// Returns the new revisions to append to `self_revisions`, with `parent`
+/// already adjusted to index into the combined (`self_revisions` then these)
+/// sequence.
+pub(super) fn merge(
+    self_revisions: &[Revision],
+    other_revisions: &[Revision],
+    common: usize,
+) -> Result<Vec<Revision>, StateError> {
+    let self_replay = replay(self_revisions);
+    let other_replay = replay(other_revisions);
+
+    let shared_union_len = if common == 0 {
+        0
+    } else {
+        self_replay.revisions[common - 1].union_len_after
+    };
+
+    // `other`'s revisions before `common` produced a union-string prefix
+    // identical to `self`'s (that's what "common" means), so anything at or past
+    // `shared_union_len` in `other`'s union string is text `other` inserted on
+    // its own branch. Appended after everything `self` already has, it keeps
+    // `other`'s internal insertion order and gets fresh, non-colliding union
+    // coordinates.
+    let remap = |pos: usize| -> usize {
+        if pos < shared_union_len {
+            pos
+        } else {
+            pos - shared_union_len + self_replay.union.len()
+        }
+    };
+
+    // `live_by_index[i]` is the live-to-union mapping at node `i` of the
+    // combined tree: `self`'s own nodes keep `self_replay`'s per-revision
+    // snapshots (indexed identically, since spliced revisions are appended
+    // after them), and each newly spliced revision appends its own snapshot as
+    // it's built below. A spliced revision's starting state is always read
+    // from *its own parent's* entry here, never from whichever revision was
+    // processed immediately before it, so sibling branches in `other` (two
+    // children of the same parent) each start from the right place.
+    let mut live_by_index: Vec<Vec<usize>> = self_replay.live_to_union;
+
+    let base = self_revisions.len();
+    let mut spliced = Vec::with_capacity(other_revisions.len() - common);
+
+    for (offset, revision) in other_revisions[common..].iter().enumerate() {
+        let union_rev = &other_replay.revisions[common + offset];
+
+        let mut deletes = Subset::empty();
+        for r in &union_rev.deletes.0 {
+            // A single delete range can straddle the shared/unique boundary (e.g.
+            // deleting a span that covers the tail of the shared text and the
+            // start of `other`'s own inserted text), so split it there rather
+            // than remapping the endpoints independently.
+            if r.start < shared_union_len && r.end > shared_union_len {
+                deletes.push(remap(r.start)..remap(shared_union_len));
+                deletes.push(remap(shared_union_len)..remap(r.end));
+            } else {
+                deletes.push(remap(r.start)..remap(r.end));
+            }
+        }
+
+        // Each insert keeps its own anchor and range, remapped independently:
+        // unlike a delete range, a single `Insert`'s union positions are always
+        // either entirely shared or entirely unique to `other` (they're all
+        // created in one run, in one revision), so they can never straddle
+        // `shared_union_len` the way a delete span can.
+        let insert_text: Vec<Vec<char>> = union_rev
+            .inserts
+            .iter()
+            .map(|(_, range)| other_replay.union[range.clone()].to_vec())
+            .collect();
+        let splice_inserts: Vec<SplicedInsert> = union_rev
+            .inserts
+            .iter()
+            .zip(&insert_text)
+            .map(|((anchor, range), text)| {
+                let start = remap(range.start);
+                SplicedInsert {
+                    anchor: anchor.map(remap),
+                    text,
+                    union_range: start..start + (range.end - range.start),
+                }
+            })
+            .collect();
+
+        let parent = if revision.parent >= common {
+            base + (revision.parent - common)
+        } else {
+            revision.parent
+        };
+        debug_assert!(parent < base + offset);
+
+        let parent_live = &live_by_index[parent];
+        let (changes, inverse, next_live) = splice(parent_live, &deletes, &splice_inserts);
+        live_by_index.push(next_live);
+
+        spliced.push(Revision {
+            parent,
+            last_child: None,
+            transaction: Arc::new(Transaction {
+                changes,
+                selection: revision.transaction.selection.clone(),
+            }),
+            inversion: Arc::new(Transaction {
+                changes: inverse,
+                selection: revision.inversion.selection.clone(),
+            }),
+            timestamp: revision.timestamp,
+        });
+    }
+
+    Ok(spliced)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::SystemTime;
+
+    use super::*;
+
+    fn revision(parent: usize, changes: ChangeSet) -> Revision {
+        Revision {
+            parent,
+            last_child: None,
+            transaction: Arc::new(Transaction {
+                changes,
+                selection: None,
+            }),
+            inversion: Arc::new(Transaction {
+                changes: ChangeSet {
+                    changes: Vec::new(),
+                    len: 0,
+                    len_after: 0,
+                },
+                selection: None,
+            }),
+            timestamp: SystemTime::UNIX_EPOCH,
+        }
+    }
+
+    fn root() -> Revision {
+        revision(
+            0,
+            ChangeSet {
+                changes: Vec::new(),
+                len: 0,
+                len_after: 0,
+            },
+        )
+    }
+
+    fn insert(text: &str) -> ChangeSet {
+        ChangeSet {
+            changes: vec![Operation::Insert(text.into())],
+            len: 0,
+            len_after: text.chars().count(),
+        }
+    }
+
+    /// A two-revision branch (both children of the same parent) must each start
+    /// from *that parent's* live text, not from whatever the previously-spliced
+    /// sibling produced. Regression test for the `replay`/`merge` bug where
+    /// `live_to_union` was threaded by loop order instead of `revision.parent`.
+    #[test]
+    fn merge_handles_a_branch_of_sibling_revisions() {
+        let self_revisions = vec![root()];
+        let other_revisions = vec![
+            root(),
+            revision(0, insert("X")),
+            // Sibling of the revision above - also a child of the root, not a
+            // continuation of it.
+            revision(0, insert("Y")),
+        ];
+
+        let common = common_prefix_len(&self_revisions, &other_revisions);
+        assert_eq!(common, 1);
+
+        let spliced = merge(&self_revisions, &other_revisions, common).unwrap();
+        assert_eq!(spliced.len(), 2);
+
+        // Both spliced revisions are children of the root (index 0), not of each
+        // other.
+        assert_eq!(spliced[0].parent, 0);
+        assert_eq!(spliced[1].parent, 0);
+
+        // The second sibling inserts "Y" into the *empty* text it actually branched
+        // from, so its changeset has no leading `Retain` and `len == 0` - not `len
+        // == 1` (which is what loop-order threading would produce, since it would
+        // wrongly start from the first sibling's "X").
+        assert_eq!(spliced[1].transaction.changes.len, 0);
+        assert_eq!(
+            spliced[1].transaction.changes.changes(),
+            &[Operation::Insert("Y".into())]
+        );
+    }
+
+    /// `other`'s prepend must transform to a prepend, not collapse to an append
+    /// at the tail of `self`'s entire union string. Regression test for the
+    /// bug where an insert's position was read off
+    /// its own (freshly-created, always-past-the-end) union value instead of an
+    /// anchor to the character it actually preceded.
+    #[test]
+    fn merge_preserves_insert_position_relative_to_the_shared_ancestor() {
+        // Both sides share a root and one insert of "AB"; self then appends "S"
+        // ("AB" -> "ABS"), other independently prepends "O" ("AB" -> "OAB").
+        let ab = ChangeSet {
+            changes: vec![Operation::Insert("AB".into())],
+            len: 0,
+            len_after: 2,
+        };
+        let append_s = ChangeSet {
+            changes: vec![Operation::Retain(2), Operation::Insert("S".into())],
+            len: 2,
+            len_after: 3,
+        };
+        let prepend_o = ChangeSet {
+            changes: vec![Operation::Insert("O".into()), Operation::Retain(2)],
+            len: 2,
+            len_after: 3,
+        };
+
+        let self_revisions = vec![root(), revision(0, ab.clone()), revision(1, append_s)];
+        let other_revisions = vec![root(), revision(0, ab), revision(1, prepend_o)];
+
+        let common = common_prefix_len(&self_revisions, &other_revisions);
+        assert_eq!(common, 2);
+
+        let spliced = merge(&self_revisions, &other_revisions, common).unwrap();
+        assert_eq!(spliced.len(), 1);
+
+        // Branches from the shared "AB" ancestor (index 1), the same node
+        // `self`'s own "S" revision branches from - not from `self`'s head.
+        assert_eq!(spliced[0].parent, 1);
+
+        // "O" transforms to an insert *before* the retained "AB", not after it:
+        // collapsing it to a tail append would force `len` up to 3 (self's own
+        // head length) and reorder the ops to `[Retain(2), Insert("O")]`.
+        assert_eq!(spliced[0].transaction.changes.len, 2);
+        assert_eq!(
+            spliced[0].transaction.changes.changes(),
+            &[Operation::Insert("O".into()), Operation::Retain(2)]
+        );
+    }
+
+    /// A single transaction with more than one `Insert` (a multi-cursor edit)
+    /// must keep each insert's own position instead of collapsing them into one
+    /// blob. Regression test for `replay` capturing `insert_start` once per
+    /// revision instead of once per `Insert` op.
+    #[test]
+    fn merge_keeps_each_insert_in_a_multi_insert_transaction_separate() {
+        let abcd = ChangeSet {
+            changes: vec![Operation::Insert("ABCD".into())],
+            len: 0,
+            len_after: 4,
+        };
+        // Insert "X" before "AB" and "Y" between "AB" and "CD": "ABCD" -> "XABYCD".
+        let multi_insert = ChangeSet {
+            changes: vec![
+                Operation::Insert("X".into()),
+                Operation::Retain(2),
+                Operation::Insert("Y".into()),
+                Operation::Retain(2),
+            ],
+            len: 4,
+            len_after: 6,
+        };
+
+        let self_revisions = vec![root(), revision(0, abcd.clone())];
+        let other_revisions = vec![root(), revision(0, abcd), revision(1, multi_insert)];
+
+        let common = common_prefix_len(&self_revisions, &other_revisions);
+        assert_eq!(common, 2);
+
+        let spliced = merge(&self_revisions, &other_revisions, common).unwrap();
+        assert_eq!(spliced.len(), 1);
+
+        // Nothing diverged before this revision, so the transform is an
+        // identity: both inserts land exactly where they were recorded, not
+        // merged into a single blob at one point.
+        assert_eq!(spliced[0].transaction.changes.len, 4);
+        assert_eq!(
+            spliced[0].transaction.changes.changes(),
+            &[
+                Operation::Insert("X".into()),
+                Operation::Retain(2),
+                Operation::Insert("Y".into()),
+                Operation::Retain(2),
+            ]
+        );
+    }
+}
@@ -1,14 +1,57 @@
 use std::{
     fs::File,
-    io::{Seek, Write},
+    io::{self, Read, Seek, Write},
+    path::Path,
     time::SystemTime,
 };
 
+use memmap2::Mmap;
+
 use crate::{
+    combinators::*,
     hash::Digest,
-    history::{History, Revision},
+    history::{error::StateError, History, Revision},
 };
 
+/// One fixed-width entry per revision in the undofile's on-disk index, mirroring
+/// Mercurial revlog's `INDEX_ENTRY_SIZE` entries: `byte_offset` and the stored/
+/// uncompressed lengths let a revision's payload be read with a single seek, `parent`
+/// lets a root-to-node chain be walked without touching any other revision, and
+/// `flags` carries the compression bit (and, for censored revisions, the redaction
+/// bit) instead of a dedicated field per concern.
+#[derive(Clone, Copy)]
+pub struct IndexEntry {
+    pub byte_offset: u64,
+    pub stored_len: u32,
+    pub uncompressed_len: u32,
+    pub parent: u32,
+    pub flags: u16,
+}
+
+pub const INDEX_ENTRY_SIZE: usize =
+    std::mem::size_of::<u64>() + std::mem::size_of::<u32>() * 3 + std::mem::size_of::<u16>();
+
+impl IndexEntry {
+    pub(crate) fn serialize<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_u64(writer, self.byte_offset)?;
+        write_u32(writer, self.stored_len)?;
+        write_u32(writer, self.uncompressed_len)?;
+        write_u32(writer, self.parent)?;
+        write_u16(writer, self.flags)?;
+        Ok(())
+    }
+
+    pub(crate) fn deserialize<R: Read>(reader: &mut R) -> io::Result<Self> {
+        Ok(Self {
+            byte_offset: read_u64(reader)?,
+            stored_len: read_u32(reader)?,
+            uncompressed_len: read_u32(reader)?,
+            parent: read_u32(reader)?,
+            flags: read_u16(reader)?,
+        })
+    }
+}
+
 /// TODO: Memorymap
 /// ```
 /// A -> (A->B) -> (B->C) -> (C->D)
@@ -61,8 +104,14 @@ pub struct UndoMapNode {
     pub hash: Digest,
     /// Parent node
     pub parent: Option<usize>,
-    /// Number of revisions
+    /// Number of revisions contributed by this node
     pub changes: usize,
+    /// Cumulative revision count through this node (i.e. this node's offset into the
+    /// full, reconstructed `History`). Mirrors the on-disk index's `byte_offset`
+    /// precomputation, and for the same reason: it lets `commit` look up an
+    /// ancestor's offset in O(1) instead of walking and summing every node up the
+    /// tree.
+    pub total_revisions: usize,
 }
 
 // Interface for serializing/deserializing into storage or map,etc.
@@ -76,49 +125,122 @@ pub struct UndoStorageHandle<'a> {
     - The UndoFile will be an interface singleton on all clients. It will help to merge client histories too. It will store the parent hash/idx too.
 */
 impl UndoMap {
-    // TODO: Add fast path when undofile state is unchanged. Maybe a UUID?
-    // TODO: Make panic-free
-    pub fn commit(&mut self, history: &History, file_hash: Digest) {
+    // The undofile header now carries a cheap size/mtime pre-check (see
+    // `History::parse_header`) for "has the target file changed?"; TODO: thread that
+    // same signal through here so `commit` can skip rebuilding a node's diff entirely
+    // when the caller already knows nothing changed, instead of only avoiding the
+    // full-file hash.
+    pub fn commit(&mut self, history: &History, file_hash: Digest) -> Result<(), StateError> {
         // First, I need to construct the diff
-        // - Traverse up the undo tree to find the offset
-        if let Some((mut parent_idx, parent_hash)) = history.undofile_parent {
-            // TODO: Check if the parent's hash matches the one at the idx
-            // Sum number of revisions in each
+        // - Find this commit's offset into `history`'s revisions
+        if let Some((parent_idx, parent_hash)) = history.undofile_parent {
+            // A parent recorded by `history` but no longer matching what's stored
+            // under that index means the map and the history it's tracking have
+            // drifted — e.g. another client rewrote that node in between — so this
+            // commit has nothing reliable to offset from.
             if self.nodes[parent_idx].hash != parent_hash {
-                todo!()
+                return Err(StateError::InvalidData(format!(
+                    "undofile commit: parent node {} hash does not match history's recorded parent hash",
+                    parent_idx
+                )));
             }
 
-            let mut offset = 0;
-            loop {
-                let node = &self.nodes[parent_idx];
-                offset += node.diff.revisions.len();
-                if let Some(ancestor_idx) = node.parent {
-                    parent_idx = ancestor_idx;
-                } else {
-                    break;
-                }
-            }
-            let revisions = history.get_revisions()[offset..].to_vec();
-            let diff = UndoStateDiff {
-                revisions,
-                current: history.current_revision(),
-            };
-            self.nodes.push(UndoStorageNode {
+            let offset = self.nodes[parent_idx].total_revisions;
+            let changes = history.get_revisions().len() - offset;
+            self.nodes.push(UndoMapNode {
                 hash: file_hash,
-                parent: Some(self.nodes.len() - 1),
-                diff,
+                parent: Some(parent_idx),
+                changes,
+                total_revisions: offset + changes,
             });
         } else {
-            let diff = UndoStateDiff {
-                revisions: history.get_revisions().to_vec(),
-                current: history.current_revision(),
-            };
-            self.nodes.push(UndoStorageNode {
+            let changes = history.get_revisions().len();
+            self.nodes.push(UndoMapNode {
                 hash: file_hash,
                 parent: None,
-                diff,
+                changes,
+                total_revisions: changes,
             });
         }
+
+        Ok(())
+    }
+}
+
+impl<'a> UndoStorageHandle<'a> {
+    /// Reads just the on-disk index (header + index table) of this handle's
+    /// undofile, without touching any revision payloads. `path` is the target file
+    /// the header is checked against for staleness, not the undofile itself.
+    pub fn read_index(&mut self, path: &Path) -> Result<Vec<IndexEntry>, StateError> {
+        let header = History::parse_header(&mut self.file, path)?;
+        History::read_index(&mut self.file, &header)
+    }
+
+    /// Memory-maps the undofile and reconstructs a single revision by seeking
+    /// directly to its payload via `entry`, without materializing the whole
+    /// `Vec<Revision>`.
+    pub fn load_revision(&self, entry: &IndexEntry) -> Result<Revision, StateError> {
+        let mmap = unsafe { Mmap::map(&self.file)? };
+        Self::decode_at(&mmap, entry)
+    }
+
+    /// Walks `parent` links through `index`, starting at `rev`, to reconstruct the
+    /// root-to-node chain ending there. Maps the file once and only ever touches the
+    /// pages each ancestor's payload lives on, rather than loading every revision in
+    /// the tree.
+    pub fn load_chain(
+        &self,
+        index: &[IndexEntry],
+        rev: usize,
+    ) -> Result<Vec<Revision>, StateError> {
+        let mmap = unsafe { Mmap::map(&self.file)? };
+        let mut chain = Vec::new();
+        let mut current = rev;
+        loop {
+            // `parent` comes straight from the decoded (untrusted/corruptible)
+            // payload, same as `decode_at`'s byte range: a crash mid-write or
+            // hand-corruption can point it anywhere, including past `index` or
+            // into a cycle that never reaches 0. Bounds-check each lookup and cap
+            // the walk at `index.len()` steps rather than let either panic or
+            // hang the process.
+            if chain.len() > index.len() {
+                return Err(StateError::InvalidData(format!(
+                    "corrupt undofile index: parent chain from revision {} does not terminate at the root",
+                    rev
+                )));
+            }
+            let entry = index.get(current).ok_or_else(|| {
+                StateError::InvalidData(format!(
+                    "corrupt undofile index: revision {} has no entry for parent {}",
+                    rev, current
+                ))
+            })?;
+            let revision = Self::decode_at(&mmap, entry)?;
+            let parent = revision.parent;
+            chain.push(revision);
+            if current == 0 {
+                break;
+            }
+            current = parent;
+        }
+        chain.reverse();
+        Ok(chain)
+    }
+
+    fn decode_at(mmap: &Mmap, entry: &IndexEntry) -> Result<Revision, StateError> {
+        let start = (crate::history::format::DATA_START + entry.byte_offset) as usize;
+        let end = start + entry.stored_len as usize;
+        // Unlike every other on-disk-data path in this file, `entry` comes straight
+        // from the index with no further validation, so a crash mid-write (this file
+        // is never fsynced) or hand-corruption can point it past the mapped region.
+        // Bounds-check rather than let that panic the process.
+        let stored = mmap.get(start..end).ok_or_else(|| {
+            StateError::InvalidData(format!(
+                "corrupt undofile index: revision at byte {} (len {}) exceeds file",
+                entry.byte_offset, entry.stored_len
+            ))
+        })?;
+        Revision::decode(entry.flags, entry.uncompressed_len, stored)
     }
 }
 